@@ -1,15 +1,113 @@
 use std::time;
 use async_openai::Client;
 use async_openai::config::OpenAIConfig;
-use async_openai::types::{ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs, Role};
+use async_openai::types::{ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestToolMessageArgs, ChatCompletionRequestUserMessageArgs, ChatCompletionToolChoiceOption, ChatCompletionToolType, CreateChatCompletionRequestArgs, FunctionCall, Role};
+use futures::StreamExt;
+use teloxide::ApiError;
 use teloxide::Bot;
+use teloxide::RequestError;
 use teloxide::dispatching::UpdateHandler;
 use teloxide::macros::BotCommands;
+use teloxide::net::Download;
 use teloxide::prelude::*;
-use teloxide::types::{BotCommand, ChatMemberKind, InlineKeyboardButton, InlineKeyboardMarkup, MenuButton};
+use teloxide::types::{BotCommand, ChatMemberKind, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, MenuButton, MessageId};
 
 use crate::AppConfig;
-use crate::kinda_db::KindaDb;
+use crate::kinda_db::{self, KindaDb};
+use crate::rag::{self, VectorStore};
+use crate::roles::{self, DEFAULT_ROLE};
+use crate::tools::ToolRegistry;
+
+// Safety valve for the tool-calling loop below: a misbehaving model could in
+// principle keep requesting tools forever, so we bail out after this many rounds.
+const MAX_TOOL_ITERATIONS: u8 = 5;
+
+// How often the streamed placeholder message gets edited, to stay well clear of
+// Telegram's per-chat rate limits.
+const EDIT_THROTTLE: time::Duration = time::Duration::from_secs(1);
+const EDIT_THROTTLE_CHARS: usize = 200;
+const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+
+// How many turns are shown per `/history` page before the user has to page through.
+const HISTORY_PAGE_SIZE: usize = 10;
+
+#[derive(Default, Clone)]
+struct ToolCallAcc {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+impl ToolCallAcc {
+    fn into_tool_call(self) -> ChatCompletionMessageToolCall {
+        ChatCompletionMessageToolCall {
+            id: self.id,
+            r#type: ChatCompletionToolType::Function,
+            function: FunctionCall {
+                name: self.name,
+                arguments: self.arguments,
+            },
+        }
+    }
+}
+
+fn split_for_telegram(text: &str) -> Vec<String> {
+    if text.is_empty() {
+        return vec![String::new()];
+    }
+
+    text.chars()
+        .collect::<Vec<_>>()
+        .chunks(TELEGRAM_MESSAGE_LIMIT)
+        .map(|c| c.iter().collect())
+        .collect()
+}
+
+// Embeds the incoming question and pulls back the closest stored document
+// chunks for this chat, trimmed to the injected-context budget.
+async fn retrieve_context(
+    gpt_client: &Client<OpenAIConfig>,
+    vector_store: &VectorStore,
+    chat_id: ChatId,
+    question: &str,
+) -> Option<String> {
+    let query_embedding = rag::embed_query(gpt_client, question).await.ok()?;
+    let chunks = vector_store.search(chat_id, &query_embedding, rag::DEFAULT_TOP_K).await.ok()?;
+
+    let mut context = String::new();
+    for (source, text) in chunks {
+        let piece = format!("[{}]\n{}\n\n", source, text);
+        if context.len() + piece.len() > rag::MAX_CONTEXT_CHARS {
+            break;
+        }
+        context.push_str(&piece);
+    }
+
+    if context.is_empty() {
+        None
+    } else {
+        Some(context)
+    }
+}
+
+// Edits the placeholder message, swallowing the "message is not modified" error
+// and, on Telegram's flood control, waiting the requested time and retrying with
+// no retry limit; any other error is logged and the edit is dropped.
+async fn edit_with_retry(bot: &Bot, chat_id: ChatId, msg_id: MessageId, text: &str) {
+    loop {
+        match bot.edit_message_text(chat_id, msg_id, text).await {
+            Ok(_) => break,
+            Err(RequestError::Api(ApiError::MessageNotModified)) => break,
+            Err(RequestError::RetryAfter(retry_after)) => {
+                tokio::time::sleep(retry_after.duration()).await;
+            }
+            Err(e) => {
+                log::warn!("failed to edit message in {}: {}", chat_id, e);
+                break;
+            }
+        }
+    }
+}
 
 trait UserName {
     fn get_user_name(&self) -> String;
@@ -28,6 +126,12 @@ pub enum Command {
     Start,
     #[command(description = "начать новый разговор (полезно, чтобы не перегружать бота)")]
     New,
+    #[command(description = "выбрать роль ассистента")]
+    Role,
+    #[command(description = "показать историю переписки")]
+    History,
+    #[command(description = "забыть загруженные документы этого чата")]
+    Forget,
 }
 
 type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
@@ -41,9 +145,27 @@ pub fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'stat
             Update::filter_message()
                 .filter_command::<Command>()
                 .branch(case![Command::Start].endpoint(start))
-                .branch(case![Command::New].endpoint(new_chat)),
+                .branch(case![Command::New].endpoint(new_chat))
+                .branch(case![Command::Role].endpoint(role_command))
+                .branch(case![Command::History].endpoint(history_command))
+                .branch(case![Command::Forget].endpoint(forget_command)),
+        )
+        .branch(
+            Update::filter_message()
+                .filter(|msg: Message| msg.document().is_some())
+                .endpoint(ingest_document),
         )
         .branch(Update::filter_message().endpoint(chat_msg))
+        .branch(
+            Update::filter_callback_query()
+                .filter(|q: CallbackQuery| q.data.as_deref().is_some_and(|d| d.starts_with("role-")))
+                .endpoint(role_callback),
+        )
+        .branch(
+            Update::filter_callback_query()
+                .filter(|q: CallbackQuery| q.data.as_deref().is_some_and(|d| d.starts_with("history-")))
+                .endpoint(history_callback),
+        )
         .branch(Update::filter_callback_query().endpoint(admin_callback))
 }
 
@@ -52,6 +174,8 @@ pub async fn chat_msg(
     msg: Message,
     db: KindaDb,
     gpt_client: Client<OpenAIConfig>,
+    tool_registry: ToolRegistry,
+    vector_store: VectorStore,
 ) -> HandlerResult {
     let user_name = msg.get_user_name();
     let msg_txt = msg.text().unwrap_or("");
@@ -59,19 +183,26 @@ pub async fn chat_msg(
 
     let is_user_accepted = db.is_accepted(msg.chat.id).await;
     if is_user_accepted {
-        let mut chat_prev = db.chat_prev(msg.chat.id).await;
+        db.maybe_summarize(msg.chat.id, &gpt_client).await?;
+        let mut chat_prev = db.chat_prev(msg.chat.id).await?;
+        let role = db.role(msg.chat.id).await.unwrap_or_else(|| DEFAULT_ROLE.to_string());
 
         let mut msgs = vec![
             ChatCompletionRequestSystemMessageArgs::default()
-                .content("Ты ассистент и секретарь. Твой основной язык русский. \
-                Ты помогаешь вести деловую переписку и искать нужную информацию. \
-                Так же ты хороший переводчик и владеешь всеми языками мира. \
-                Ты опытен в составлении статей и имеешь широкий кругозор в науках и \
-                программировании.")
+                .content(roles::system_prompt_for(&role))
                 .build()?
                 .into()
         ];
 
+        if let Some(context) = retrieve_context(&gpt_client, &vector_store, msg.chat.id, msg_txt).await {
+            msgs.push(
+                ChatCompletionRequestSystemMessageArgs::default()
+                    .content(format!("Контекст из загруженных документов:\n\n{}", context))
+                    .build()?
+                    .into(),
+            );
+        }
+
         let new_request_msg = ChatCompletionRequestUserMessageArgs::default()
             .content(msg_txt)
             .build()?
@@ -80,34 +211,147 @@ pub async fn chat_msg(
         msgs.append(&mut chat_prev);
         msgs.push(new_request_msg);
 
-        log::info!("building request from {}", msg.chat.id);
-        let request = CreateChatCompletionRequestArgs::default()
-            .max_tokens(4096u16)
-            .model("gpt-4o")
-            .messages(msgs)
-            .build()?;
-        log::info!("request built from {}", msg.chat.id);
+        db.add_to_chat(msg.chat.id, Role::User, msg_txt.to_string()).await?;
+        log::info!("orig msg added to chat {}", msg.chat.id);
 
-        let response = gpt_client.chat().create(request).await?;
-        log::info!("got response to {}", msg.chat.id);
+        let placeholder = bot.send_message(msg.chat.id, "…").await?;
+        let placeholder_id = placeholder.id;
+
+        let mut iterations = 0u8;
+        let response_txt = loop {
+            log::info!("building request from {}", msg.chat.id);
+            let mut request_builder = CreateChatCompletionRequestArgs::default();
+            request_builder
+                .max_tokens(4096u16)
+                .model("gpt-4o")
+                .messages(msgs.clone());
+
+            if !tool_registry.is_empty() {
+                request_builder
+                    .tools(tool_registry.openai_tools())
+                    .tool_choice(ChatCompletionToolChoiceOption::Auto);
+            }
 
-        db.add_to_chat(msg.chat.id, Role::User, msg_txt.to_string()).await;
-        log::info!("orig msg added to chat {}", msg.chat.id);
+            let request = request_builder.build()?;
+            log::info!("request built from {}", msg.chat.id);
 
-        for choice in response.choices {
-            let response_txt = choice.message.content.unwrap_or("".to_string());
+            let mut stream = gpt_client.chat().create_stream(request).await?;
+            log::info!("stream opened for {}", msg.chat.id);
 
-            while bot
-                .send_message(msg.chat.id, response_txt.clone())
-                .await
-                .is_err() {
-                tokio::time::sleep(time::Duration::from_secs(1)).await;
+            let mut content_acc = String::new();
+            let mut tool_call_accs: Vec<ToolCallAcc> = vec![];
+            let mut last_edit = tokio::time::Instant::now();
+            let mut last_edited_len = 0usize;
+
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                let Some(choice) = chunk.choices.into_iter().next() else { continue };
+
+                if let Some(delta) = choice.delta.content {
+                    content_acc.push_str(&delta);
+                }
+
+                for tc in choice.delta.tool_calls.unwrap_or_default() {
+                    let idx = tc.index as usize;
+                    while tool_call_accs.len() <= idx {
+                        tool_call_accs.push(ToolCallAcc::default());
+                    }
+
+                    let acc = &mut tool_call_accs[idx];
+                    if let Some(id) = tc.id {
+                        acc.id = id;
+                    }
+                    if let Some(f) = tc.function {
+                        if let Some(name) = f.name {
+                            acc.name.push_str(&name);
+                        }
+                        if let Some(args) = f.arguments {
+                            acc.arguments.push_str(&args);
+                        }
+                    }
+                }
+
+                let due = last_edit.elapsed() >= EDIT_THROTTLE
+                    || content_acc.len().saturating_sub(last_edited_len) >= EDIT_THROTTLE_CHARS;
+
+                if !content_acc.is_empty() && due {
+                    let preview = split_for_telegram(&content_acc).remove(0);
+                    edit_with_retry(&bot, msg.chat.id, placeholder_id, &preview).await;
+                    last_edit = tokio::time::Instant::now();
+                    last_edited_len = content_acc.len();
+                }
             }
 
-            log::info!("response sent to {}", msg.chat.id);
-            db.add_to_chat(msg.chat.id, Role::Assistant, response_txt).await;
-            log::info!("response msg added to chat {}", msg.chat.id);
+            if tool_call_accs.is_empty() {
+                break content_acc;
+            }
+
+            iterations += 1;
+            if iterations > MAX_TOOL_ITERATIONS {
+                log::warn!("tool-call loop capped for {}", msg.chat.id);
+                // A tool-calls-only final chunk leaves `content_acc` empty, which
+                // would otherwise strand the "…" placeholder with no explanation
+                // (an empty edit is rejected by Telegram and silently dropped).
+                break if content_acc.is_empty() {
+                    "Не удалось получить ответ: превышен лимит вызовов инструментов. Попробуйте переформулировать запрос.".to_string()
+                } else {
+                    content_acc
+                };
+            }
+
+            let tool_calls: Vec<ChatCompletionMessageToolCall> = tool_call_accs
+                .into_iter()
+                .map(ToolCallAcc::into_tool_call)
+                .collect();
+
+            msgs.push(
+                ChatCompletionRequestAssistantMessageArgs::default()
+                    .tool_calls(tool_calls.clone())
+                    .build()?
+                    .into(),
+            );
+            // Persisted so `chat_prev` replays the tool-call/tool-result pair in
+            // order next turn; without this the DB would hand the model a
+            // Role::Tool message with no preceding tool_calls declaration.
+            db.add_to_chat(
+                msg.chat.id,
+                Role::Assistant,
+                serde_json::to_string(&tool_calls).unwrap_or_default(),
+            ).await?;
+
+            for tool_call in tool_calls {
+                log::info!("dispatching tool {} for {}", tool_call.function.name, msg.chat.id);
+                let args = serde_json::from_str(&tool_call.function.arguments).unwrap_or_default();
+                let result = match tool_registry.dispatch(&tool_call.function.name, args).await {
+                    Ok(result) => result,
+                    Err(e) => format!("tool error: {}", e),
+                };
+
+                msgs.push(
+                    ChatCompletionRequestToolMessageArgs::default()
+                        .tool_call_id(tool_call.id.clone())
+                        .content(result.clone())
+                        .build()?
+                        .into(),
+                );
+                db.add_to_chat(msg.chat.id, Role::Tool, kinda_db::encode_tool_result(tool_call.id, result)).await?;
+            }
+        };
+
+        let mut reply_chunks = split_for_telegram(&response_txt).into_iter();
+        if let Some(first) = reply_chunks.next() {
+            edit_with_retry(&bot, msg.chat.id, placeholder_id, &first).await;
+        }
+
+        for rest in reply_chunks {
+            while bot.send_message(msg.chat.id, rest.clone()).await.is_err() {
+                tokio::time::sleep(time::Duration::from_secs(1)).await;
+            }
         }
+
+        log::info!("response sent to {}", msg.chat.id);
+        db.add_to_chat(msg.chat.id, Role::Assistant, response_txt).await?;
+        log::info!("response msg added to chat {}", msg.chat.id);
     } else {
         bot.send_message(msg.chat.id, "Ваша заявка ещё не подтверждена").await?;
     }
@@ -119,7 +363,7 @@ pub async fn start(bot: Bot, msg: Message, db: KindaDb, app_cfg: AppConfig) -> H
     let user_name = msg.get_user_name();
     log::info!("{} {} joined",user_name,msg.chat.id);
 
-    db.register(msg.chat.id).await;
+    db.register(msg.chat.id).await?;
 
     let admin_btn_rows = vec![
         InlineKeyboardButton::callback("✅", format!("accept-{}", msg.chat.id)),
@@ -130,7 +374,12 @@ pub async fn start(bot: Bot, msg: Message, db: KindaDb, app_cfg: AppConfig) -> H
         .reply_markup(InlineKeyboardMarkup::new(vec![admin_btn_rows]))
         .await?;
 
-    bot.set_my_commands(vec![BotCommand::new("new", "начать новый разговор (полезно, чтобы не перегружать бота)")]).await?;
+    bot.set_my_commands(vec![
+        BotCommand::new("new", "начать новый разговор (полезно, чтобы не перегружать бота)"),
+        BotCommand::new("role", "выбрать роль ассистента"),
+        BotCommand::new("history", "показать историю переписки"),
+        BotCommand::new("forget", "забыть загруженные документы этого чата"),
+    ]).await?;
 
     bot.set_chat_menu_button()
         .chat_id(msg.chat.id)
@@ -147,13 +396,193 @@ pub async fn new_chat(bot: Bot, msg: Message, db: KindaDb) -> HandlerResult {
     if is_user_accepted {
         let user_name = msg.get_user_name();
         log::info!("{} {} started new chat", user_name, msg.chat.id);
-        db.reset_chat(msg.chat.id).await;
+        db.reset_chat(msg.chat.id).await?;
         bot.send_message(msg.chat.id, "Советчик к Вашим услугам").await?;
     }
 
     Ok(())
 }
 
+pub async fn role_command(bot: Bot, msg: Message, db: KindaDb) -> HandlerResult {
+    let is_user_accepted = db.is_accepted(msg.chat.id).await;
+
+    if is_user_accepted {
+        let role_btns = roles::built_in_roles()
+            .iter()
+            .map(|r| vec![InlineKeyboardButton::callback(r.title, format!("role-{}", r.id))])
+            .collect();
+
+        bot.send_message(msg.chat.id, "Выберите роль ассистента:")
+            .reply_markup(InlineKeyboardMarkup::new(role_btns))
+            .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn role_callback(bot: Bot, db: KindaDb, q: CallbackQuery) -> HandlerResult {
+    let chat_id = q.message.unwrap().chat.id;
+
+    if let Some(role_id) = q.data.as_deref().and_then(|d| d.strip_prefix("role-")) {
+        match roles::find_role(role_id) {
+            Some(role) => {
+                db.set_role(chat_id, role_id.to_string()).await?;
+                log::info!("{} {} picked role {}", q.from.full_name(), chat_id, role_id);
+                bot.send_message(chat_id, format!("Роль изменена: {}", role.title)).await?;
+            }
+            None => log::warn!("unknown role {}", role_id),
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn history_command(bot: Bot, msg: Message, db: KindaDb) -> HandlerResult {
+    let is_user_accepted = db.is_accepted(msg.chat.id).await;
+
+    if is_user_accepted {
+        send_history_page(&bot, &db, msg.chat.id, 0).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn history_callback(bot: Bot, db: KindaDb, q: CallbackQuery) -> HandlerResult {
+    let chat_id = q.message.unwrap().chat.id;
+
+    match q.data.as_deref().and_then(|d| d.strip_prefix("history-")) {
+        Some("export") => export_history(&bot, &db, chat_id).await?,
+        Some(page_str) => {
+            if let Ok(page) = page_str.parse::<usize>() {
+                send_history_page(&bot, &db, chat_id, page).await?;
+            }
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+async fn send_history_page(bot: &Bot, db: &KindaDb, chat_id: ChatId, page: usize) -> HandlerResult {
+    let history = db.chat_history(chat_id, None).await?;
+
+    if history.is_empty() {
+        bot.send_message(chat_id, "История пуста").await?;
+        return Ok(());
+    }
+
+    let total_pages = (history.len() + HISTORY_PAGE_SIZE - 1) / HISTORY_PAGE_SIZE;
+    let page = page.min(total_pages - 1);
+    let start = page * HISTORY_PAGE_SIZE;
+    let end = (start + HISTORY_PAGE_SIZE).min(history.len());
+
+    let body = history[start..end]
+        .iter()
+        .map(|(role, text)| format!("{}: {}", role_label(role), text))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let mut rows = vec![];
+
+    let mut nav_row = vec![];
+    if page > 0 {
+        nav_row.push(InlineKeyboardButton::callback("« назад", format!("history-{}", page - 1)));
+    }
+    if page + 1 < total_pages {
+        nav_row.push(InlineKeyboardButton::callback("вперёд »", format!("history-{}", page + 1)));
+    }
+    if !nav_row.is_empty() {
+        rows.push(nav_row);
+    }
+    rows.push(vec![InlineKeyboardButton::callback("📄 экспорт", "history-export")]);
+
+    // A page's turns are capped by count, not length, so a few verbose replies
+    // can still blow past Telegram's message limit; split the same way a
+    // streamed reply does and keep the nav keyboard on the last chunk.
+    let text = format!("Страница {}/{}\n\n{}", page + 1, total_pages, body);
+    let chunks = split_for_telegram(&text);
+    let last = chunks.len() - 1;
+    let mut rows = Some(rows);
+
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        let mut request = bot.send_message(chat_id, chunk);
+        if i == last {
+            if let Some(rows) = rows.take() {
+                request = request.reply_markup(InlineKeyboardMarkup::new(rows));
+            }
+        }
+        request.await?;
+    }
+
+    Ok(())
+}
+
+async fn export_history(bot: &Bot, db: &KindaDb, chat_id: ChatId) -> HandlerResult {
+    let history = db.chat_history(chat_id, None).await?;
+    let transcript = history
+        .iter()
+        .map(|(role, text)| format!("{}: {}", role_label(role), text))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let file = InputFile::memory(transcript.into_bytes()).file_name("history.txt");
+    bot.send_document(chat_id, file).await?;
+
+    Ok(())
+}
+
+fn role_label(role: &Role) -> &'static str {
+    match role {
+        Role::User => "Вы",
+        Role::Assistant => "Советчик",
+        Role::System => "Система",
+        Role::Tool => "Инструмент",
+        Role::Function => "Функция",
+    }
+}
+
+pub async fn ingest_document(
+    bot: Bot,
+    msg: Message,
+    db: KindaDb,
+    gpt_client: Client<OpenAIConfig>,
+    vector_store: VectorStore,
+) -> HandlerResult {
+    let is_user_accepted = db.is_accepted(msg.chat.id).await;
+    if !is_user_accepted {
+        return Ok(());
+    }
+
+    let Some(document) = msg.document() else { return Ok(()); };
+    let source = document.file_name.clone().unwrap_or_else(|| "document".to_string());
+
+    let file = bot.get_file(&document.file.id).await?;
+    let mut buf: Vec<u8> = Vec::new();
+    bot.download_file(&file.path, &mut buf).await?;
+    let text = String::from_utf8_lossy(&buf).to_string();
+
+    let chunks = vector_store.ingest(msg.chat.id, source.clone(), text, &gpt_client).await?;
+    log::info!("ingested {} chunks from {} for {}", chunks, source, msg.chat.id);
+
+    bot.send_message(
+        msg.chat.id,
+        format!("Документ «{}» проиндексирован: {} фрагмент(ов)", source, chunks),
+    ).await?;
+
+    Ok(())
+}
+
+pub async fn forget_command(bot: Bot, msg: Message, db: KindaDb, vector_store: VectorStore) -> HandlerResult {
+    let is_user_accepted = db.is_accepted(msg.chat.id).await;
+
+    if is_user_accepted {
+        vector_store.forget(msg.chat.id).await?;
+        bot.send_message(msg.chat.id, "Загруженные документы этого чата забыты").await?;
+    }
+
+    Ok(())
+}
+
 pub async fn admin_callback(
     bot: Bot,
     db: KindaDb,
@@ -171,12 +600,12 @@ pub async fn admin_callback(
 
             match maybe_cmd_and_chat {
                 Some(("accept", chat_id)) => {
-                    db.confirm(chat_id).await;
+                    db.confirm(chat_id).await?;
                     log::info!("{} {} accepted", q.from.full_name(), chat_id);
                     bot.send_message(chat_id, "Заявка одобрена! Советчик к Вашим услугам").await?;
                 }
                 Some(("decline", chat_id)) => {
-                    db.delete(chat_id).await;
+                    db.delete(chat_id).await?;
                     log::info!("{} {} declined", q.from.full_name(), chat_id);
                     bot.send_message(chat_id, "Заявка отклонена...").await?;
                 }
@@ -195,7 +624,7 @@ pub async fn chat_member(mmbr: ChatMemberUpdated, db: KindaDb) -> HandlerResult
 
     if new_member.kind != ChatMemberKind::Member {
         log::info!("{} {} left",mmbr.from.full_name(),mmbr.chat.id);
-        db.delete(new_member.user.id.into()).await;
+        db.delete(new_member.user.id.into()).await?;
     }
 
     Ok(())
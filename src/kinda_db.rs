@@ -1,201 +1,460 @@
-use std::collections::HashMap;
-use std::path;
-use std::sync::Arc;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
 
-use async_openai::types::{ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestFunctionMessageArgs, ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestToolMessageArgs, ChatCompletionRequestUserMessageArgs, Role};
+use async_openai::Client;
+use async_openai::config::OpenAIConfig;
+use async_openai::types::{ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestFunctionMessageArgs, ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestToolMessageArgs, ChatCompletionRequestUserMessageArgs, ChatCompletionRequestUserMessageContent, CreateChatCompletionRequestArgs, Role};
+use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
 use teloxide::prelude::ChatId;
-use tokio::fs;
-use tokio::io::AsyncWriteExt;
-use tokio::sync::RwLock;
-
-#[derive(Serialize, Deserialize, Clone)]
-enum ChatState {
-    Unconfirmed,
-    Confirmed(u64, Vec<ChatCompletionRequestMessage>),
-}
 
-type ConsistentState = Arc<RwLock<HashMap<ChatId, ChatState>>>;
+use crate::roles::DEFAULT_ROLE;
 
+// Once a chat's history crosses this many estimated tokens, the oldest turns
+// (everything but `KEEP_RECENT_TURNS`) get collapsed into one recap message.
+const SUMMARIZE_TOKEN_BUDGET: usize = 3000;
+const KEEP_RECENT_TURNS: usize = 8;
 
-// It's kinda DB:) Persists bots state in filesystem.
+// It's kinda DB:) Persists bots state in a local SQLite file, with chat metadata
+// in `chats` and the per-chat conversation in `messages`.
 #[derive(Clone)]
 pub struct KindaDb {
-    path: String,
-    state: ConsistentState,
+    conn: Arc<Mutex<Connection>>,
 }
 
 impl KindaDb {
-    pub async fn register(&self, chat_id: ChatId) {
-        let mut state = self.state.write().await;
-        state.insert(chat_id, ChatState::Unconfirmed);
-        self.save_state(&state).await;
+    pub async fn register(&self, chat_id: ChatId) -> rusqlite::Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO chats (id, state, confirmed_at, role) VALUES (?1, 'unconfirmed', NULL, NULL)
+                 ON CONFLICT(id) DO UPDATE SET state = 'unconfirmed', confirmed_at = NULL, role = NULL",
+                params![chat_id.0],
+            )?;
+            Ok(())
+        }).await
     }
 
-    pub async fn confirm(&self, chat_id: ChatId) {
-        self.reset_chat(chat_id).await;
+    pub async fn confirm(&self, chat_id: ChatId) -> rusqlite::Result<()> {
+        self.reset_chat(chat_id).await
     }
 
-    pub async fn reset_chat(&self, chat_id: ChatId) {
-        let mut state = self.state.write().await;
-        let chat_path = format!("{}/{}.txt", self.path, chat_id);
-        let _ = fs::remove_file(chat_path).await;
+    pub async fn reset_chat(&self, chat_id: ChatId) -> rusqlite::Result<()> {
+        let now = now_sec() as i64;
+
+        self.with_conn(move |conn| {
+            conn.execute("DELETE FROM messages WHERE chat_id = ?1", params![chat_id.0])?;
+
+            let role: Option<String> = conn
+                .query_row("SELECT role FROM chats WHERE id = ?1", params![chat_id.0], |row| row.get(0))
+                .ok();
+            let role = role.flatten().unwrap_or_else(|| DEFAULT_ROLE.to_string());
+
+            conn.execute(
+                "INSERT INTO chats (id, state, confirmed_at, role) VALUES (?1, 'confirmed', ?2, ?3)
+                 ON CONFLICT(id) DO UPDATE SET state = 'confirmed', confirmed_at = ?2, role = ?3",
+                params![chat_id.0, now, role],
+            )?;
+            Ok(())
+        }).await
+    }
 
-        let new_state = ChatState::Confirmed(now_sec(), vec![]);
-        state.insert(chat_id, new_state.clone());
-        self.save_state(&state).await;
+    pub async fn set_role(&self, chat_id: ChatId, role: String) -> rusqlite::Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "UPDATE chats SET role = ?2 WHERE id = ?1 AND state = 'confirmed'",
+                params![chat_id.0, role],
+            )?;
+            Ok(())
+        }).await
     }
 
-    pub async fn delete(&self, chat_id: ChatId) {
-        let mut state = self.state.write().await;
-        state.remove(&chat_id);
+    pub async fn role(&self, chat_id: ChatId) -> Option<String> {
+        self.with_conn(move |conn| {
+            Ok(conn.query_row(
+                "SELECT role FROM chats WHERE id = ?1 AND state = 'confirmed'",
+                params![chat_id.0],
+                |row| row.get::<_, Option<String>>(0),
+            ).ok().flatten())
+        }).await.unwrap_or(None)
+    }
 
-        let chat_path = format!("{}/{}.txt", self.path, chat_id);
-        let _ = fs::remove_file(chat_path).await;
-        self.save_state(&state).await;
+    pub async fn delete(&self, chat_id: ChatId) -> rusqlite::Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute("DELETE FROM chats WHERE id = ?1", params![chat_id.0])?;
+            conn.execute("DELETE FROM messages WHERE chat_id = ?1", params![chat_id.0])?;
+            Ok(())
+        }).await
     }
 
     pub async fn is_accepted(&self, chat_id: ChatId) -> bool {
-        let state = self.state.read().await;
-        match state.get(&chat_id) {
-            Some(ChatState::Confirmed(_, _)) => true,
-            _ => false
-        }
+        self.with_conn(move |conn| {
+            Ok(conn.query_row(
+                "SELECT 1 FROM chats WHERE id = ?1 AND state = 'confirmed'",
+                params![chat_id.0],
+                |_| Ok(()),
+            ).is_ok())
+        }).await.unwrap_or(false)
     }
 
-    pub async fn chat_prev(&self, chat_id: ChatId) -> Vec<ChatCompletionRequestMessage> {
-        let curr_state;
-        {
-            let state = self.state.read().await;
-            curr_state = state.get(&chat_id).cloned();
-        }
+    pub async fn chat_prev(&self, chat_id: ChatId) -> rusqlite::Result<Vec<ChatCompletionRequestMessage>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare("SELECT role, content FROM messages WHERE chat_id = ?1 ORDER BY ordinal")?;
+
+            let msgs = stmt
+                .query_map(params![chat_id.0], |row| {
+                    let role: String = row.get(0)?;
+                    let content: String = row.get(1)?;
+                    Ok((role, content))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?
+                .into_iter()
+                .map(|(role, content)| {
+                    let role: Role = serde_json::from_str(&role).unwrap();
+                    str_to_msg(role, content)
+                })
+                .collect();
+
+            Ok(msgs)
+        }).await
+    }
+
+    pub async fn add_to_chat(&self, chat_id: ChatId, role: Role, msg: String) -> rusqlite::Result<()> {
+        let role_str = serde_json::to_string(&role).unwrap();
 
-        match curr_state {
-            Some(ChatState::Confirmed(updated, msgs)) if now_sec() - updated < 1800 => msgs,
-            Some(ChatState::Confirmed(_, _)) => {
-                self.reset_chat(chat_id).await;
-                vec![]
+        self.with_conn(move |conn| {
+            let is_confirmed = conn
+                .query_row(
+                    "SELECT 1 FROM chats WHERE id = ?1 AND state = 'confirmed'",
+                    params![chat_id.0],
+                    |_| Ok(()),
+                )
+                .is_ok();
+
+            if !is_confirmed {
+                return Ok(());
             }
-            _ => vec![],
-        }
-    }
 
-    pub async fn add_to_chat(&self, chat_id: ChatId, role: Role, msg: String) {
-        let mut state = self.state.write().await;
+            let next_ordinal: i64 = conn.query_row(
+                "SELECT COALESCE(MAX(ordinal), -1) + 1 FROM messages WHERE chat_id = ?1",
+                params![chat_id.0],
+                |row| row.get(0),
+            )?;
+
+            conn.execute(
+                "INSERT INTO messages (chat_id, ordinal, role, content) VALUES (?1, ?2, ?3, ?4)",
+                params![chat_id.0, next_ordinal, role_str, msg],
+            )?;
+            Ok(())
+        }).await
+    }
 
-        if let Some(ChatState::Confirmed(conv_start, msgs)) = state.get(&chat_id) {
-            let chat_path = format!("{}/{}.txt", self.path, chat_id);
-            let mut chat_file = fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(chat_path)
-                .await
-                .unwrap();
+    // Returns the persisted turns as (role, text) pairs for display/export, most
+    // recent `limit` turns if given, the whole conversation otherwise.
+    pub async fn chat_history(&self, chat_id: ChatId, limit: Option<usize>) -> rusqlite::Result<Vec<(Role, String)>> {
+        let turns: Vec<(Role, String)> = self.with_conn(move |conn| {
+            let mut stmt = conn.prepare("SELECT role, content FROM messages WHERE chat_id = ?1 ORDER BY ordinal")?;
+
+            let turns = stmt
+                .query_map(params![chat_id.0], |row| {
+                    let role: String = row.get(0)?;
+                    let content: String = row.get(1)?;
+                    Ok((role, content))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?
+                .into_iter()
+                .map(|(role, content)| (serde_json::from_str::<Role>(&role).unwrap(), content))
+                .collect();
+
+            Ok(turns)
+        }).await?;
+
+        Ok(match limit {
+            Some(n) if turns.len() > n => turns[turns.len() - n..].to_vec(),
+            _ => turns,
+        })
+    }
 
-            let chunk = format!("{}***\n{}***\n", serde_json::to_string(&role).unwrap(), msg);
-            chat_file.write_all(chunk.as_bytes()).await.unwrap();
+    // Collapses the oldest turns of a growing chat into a single recap message so
+    // long-running conversations neither get dropped wholesale nor blow past the
+    // model's context window.
+    pub async fn maybe_summarize(&self, chat_id: ChatId, gpt_client: &Client<OpenAIConfig>) -> anyhow::Result<()> {
+        let msgs = self.chat_prev(chat_id).await?;
 
-            let req_msg = str_to_msg(role, msg);
-            let mut new_msgs = msgs.clone();
-            new_msgs.push(req_msg);
-            let new_state = ChatState::Confirmed(conv_start.clone(), new_msgs);
-            state.insert(chat_id, new_state);
+        if msgs.len() <= KEEP_RECENT_TURNS || estimate_tokens(&msgs) < SUMMARIZE_TOKEN_BUDGET {
+            return Ok(());
         }
 
-        self.save_state(&state).await;
+        let split_at = msgs.len() - KEEP_RECENT_TURNS;
+        let older = &msgs[..split_at];
+
+        let transcript = older
+            .iter()
+            .map(|m| format!("{:?}: {}", message_role(m), message_text(m)))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let summarize_request = CreateChatCompletionRequestArgs::default()
+            .model("gpt-4o")
+            .messages(vec![
+                ChatCompletionRequestSystemMessageArgs::default()
+                    .content("Кратко перескажи приведённый ниже разговор (около 200 слов), \
+                        сохранив важные факты, договорённости и открытые вопросы.")
+                    .build()?
+                    .into(),
+                ChatCompletionRequestUserMessageArgs::default()
+                    .content(transcript)
+                    .build()?
+                    .into(),
+            ])
+            .build()?;
+
+        let response = gpt_client.chat().create(summarize_request).await?;
+        let recap = response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|c| c.message.content)
+            .unwrap_or_default();
+
+        // The GPT round-trip above happens outside the DB lock, so a turn can land
+        // in between via `add_to_chat`. Re-read the live tail and rewrite it in the
+        // same locked call as the delete, rather than against the pre-GPT snapshot,
+        // so that turn is kept (and renumbered) instead of lost or ordinal-clashed.
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare("SELECT role, content FROM messages WHERE chat_id = ?1 ORDER BY ordinal")?;
+            let rows: Vec<(String, String)> = stmt
+                .query_map(params![chat_id.0], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            drop(stmt);
+
+            // Anchor on `split_at`, the count of turns the recap above already
+            // covers, not on KEEP_RECENT_TURNS against the now-possibly-larger
+            // row count — otherwise turns added after the snapshot but still
+            // inside the original "recent" window would fall outside both the
+            // recap and the new tail and be deleted by the statement below.
+            let tail_start = split_at.min(rows.len());
+            let tail = &rows[tail_start..];
+
+            conn.execute("DELETE FROM messages WHERE chat_id = ?1", params![chat_id.0])?;
+
+            conn.execute(
+                "INSERT INTO messages (chat_id, ordinal, role, content) VALUES (?1, 0, ?2, ?3)",
+                params![chat_id.0, serde_json::to_string(&Role::System).unwrap(), recap],
+            )?;
+
+            for (i, (role, content)) in tail.iter().enumerate() {
+                conn.execute(
+                    "INSERT INTO messages (chat_id, ordinal, role, content) VALUES (?1, ?2, ?3, ?4)",
+                    params![chat_id.0, (i + 1) as i64, role, content],
+                )?;
+            }
+
+            Ok(())
+        }).await?;
+
+        Ok(())
     }
 
     pub async fn new(path: String) -> KindaDb {
-        let db_path = format!("{}/db.txt", path);
-        let state = match path::Path::new(&db_path).exists() {
-            false => HashMap::default(),
-            true => {
-                let file = fs::read_to_string(&db_path).await.unwrap();
-
-                let raw: Vec<_> = file
-                    .split('\n')
-                    .filter(|&s| !s.is_empty())
-                    .collect();
-
-                let mut acc_map = HashMap::default();
-
-                for record in raw {
-                    log::info!("record is {:?}", record);
-                    let mut parts = record.split_whitespace();
-                    let chat_id = ChatId(parts.next().unwrap().parse::<i64>().unwrap());
-                    let last_access = parts.next().unwrap().parse::<u64>().unwrap();
-
-                    let chat_state = match last_access {
-                        la if la == 0 => ChatState::Unconfirmed,
-                        la => {
-                            let chat_path = format!("{}/{}.txt", path, chat_id);
-
-                            let chat_state = match path::Path::new(&chat_path).exists() {
-                                true => {
-                                    let file = fs::read_to_string(&chat_path)
-                                        .await
-                                        .unwrap();
-                                    let chat_state_vec: Vec<_> = file
-                                        .split("***\n")
-                                        .filter(|&s| !s.is_empty())
-                                        .collect();
-                                    chat_state_vec.chunks(2).map(|ch| {
-                                        let role: Role = serde_json::from_str(ch[0]).unwrap();
-                                        let msg = ch[1].to_string();
-                                        str_to_msg(role, msg)
-                                    }).collect()
-                                }
-                                false => vec![]
-                            };
-
-                            ChatState::Confirmed(la, chat_state)
-                        }
-                    };
-
-                    acc_map.insert(chat_id, chat_state);
-                }
+        let db_file = format!("{}/kinda.db", path);
 
-                acc_map
-            }
-        };
+        let conn = tokio::task::spawn_blocking(move || {
+            let conn = Connection::open(&db_file).expect("failed to open kinda.db");
+            init_schema(&conn);
+            migrate_legacy_files(&path, &conn);
+            conn
+        })
+            .await
+            .expect("kinda_db init worker panicked");
 
         KindaDb {
-            path,
-            state: Arc::new(RwLock::new(state)),
+            conn: Arc::new(Mutex::new(conn)),
         }
     }
 
-    async fn save_state(&self, state: &HashMap<ChatId, ChatState>) {
-        let db_path = format!("{}/db.txt", self.path);
-        let state_str = state.iter().fold(
-            String::new(),
-            |mut acc, (chat_id, state)| {
-                match state {
-                    ChatState::Unconfirmed => acc.push_str(&format!("{} 0\n", chat_id)),
-                    ChatState::Confirmed(la, _) => acc.push_str(&format!("{} {}\n", chat_id, la)),
-                }
-                acc
-            },
+    // Runs a blocking rusqlite call on a dedicated worker thread so the async
+    // handlers never block on file IO. Callers propagate `rusqlite::Error`
+    // instead of unwrapping inside the closure, so a failing query returns an
+    // error to the one request that hit it rather than panicking the worker
+    // thread and poisoning the shared mutex for every other chat.
+    async fn with_conn<T, F>(&self, f: F) -> rusqlite::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&conn)
+        })
+            .await
+            .expect("kinda_db worker thread panicked")
+    }
+}
+
+fn init_schema(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS chats (
+            id INTEGER PRIMARY KEY,
+            state TEXT NOT NULL,
+            confirmed_at INTEGER,
+            role TEXT
         );
+        CREATE TABLE IF NOT EXISTS messages (
+            chat_id INTEGER NOT NULL,
+            ordinal INTEGER NOT NULL,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            PRIMARY KEY (chat_id, ordinal)
+        );",
+    ).expect("failed to initialize kinda.db schema");
+}
+
+// One-time import of the legacy `db.txt`/`{chat_id}.txt` file store, run only
+// when the `chats` table is still empty so it never clobbers live SQLite data.
+fn migrate_legacy_files(dir: &str, conn: &Connection) {
+    let chats_empty = conn
+        .query_row("SELECT COUNT(*) FROM chats", [], |row| row.get::<_, i64>(0))
+        .map(|count| count == 0)
+        .unwrap_or(false);
+
+    let db_txt_path = format!("{}/db.txt", dir);
+    if !chats_empty || !std::path::Path::new(&db_txt_path).exists() {
+        return;
+    }
+
+    log::info!("migrating legacy db.txt store from {}", dir);
+    let raw = match std::fs::read_to_string(&db_txt_path) {
+        Ok(raw) => raw,
+        Err(e) => {
+            log::warn!("failed to read legacy db.txt: {}", e);
+            return;
+        }
+    };
+
+    for record in raw.split('\n').filter(|s| !s.is_empty()) {
+        let mut parts = record.split_whitespace();
+        let chat_id = match parts.next().and_then(|s| s.parse::<i64>().ok()) {
+            Some(id) => id,
+            None => continue,
+        };
+        let last_access = parts.next().and_then(|s| s.parse::<i64>().ok()).unwrap_or(0);
+        let role = parts.next().unwrap_or(DEFAULT_ROLE).to_string();
+
+        if last_access == 0 {
+            if let Err(e) = conn.execute(
+                "INSERT OR REPLACE INTO chats (id, state, confirmed_at, role) VALUES (?1, 'unconfirmed', NULL, NULL)",
+                params![chat_id],
+            ) {
+                log::warn!("skipping legacy chat {}: {}", chat_id, e);
+            }
+            continue;
+        }
+
+        if let Err(e) = conn.execute(
+            "INSERT OR REPLACE INTO chats (id, state, confirmed_at, role) VALUES (?1, 'confirmed', ?2, ?3)",
+            params![chat_id, last_access, role],
+        ) {
+            log::warn!("skipping legacy chat {}: {}", chat_id, e);
+            continue;
+        }
 
-        fs::write(db_path, state_str).await.unwrap()
+        let chat_path = format!("{}/{}.txt", dir, chat_id);
+        let chat_file = match std::fs::read_to_string(&chat_path) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+
+        let turns: Vec<_> = chat_file.split("***\n").filter(|s| !s.is_empty()).collect();
+        for (ordinal, turn) in turns.chunks(2).enumerate() {
+            if turn.len() < 2 {
+                continue;
+            }
+
+            let role: Role = match serde_json::from_str(turn[0]) {
+                Ok(role) => role,
+                Err(e) => {
+                    log::warn!("skipping malformed legacy message {}/{}: {}", chat_id, ordinal, e);
+                    continue;
+                }
+            };
+
+            if let Err(e) = conn.execute(
+                "INSERT OR REPLACE INTO messages (chat_id, ordinal, role, content) VALUES (?1, ?2, ?3, ?4)",
+                params![chat_id, ordinal as i64, serde_json::to_string(&role).unwrap(), turn[1]],
+            ) {
+                log::warn!("skipping malformed legacy message {}/{}: {}", chat_id, ordinal, e);
+            }
+        }
     }
+
+    log::info!("legacy db.txt migration complete");
+}
+
+// Role::Tool content is stored as this payload so `chat_prev` can restore the
+// `tool_call_id` the result answers, not just its text.
+#[derive(Serialize, Deserialize)]
+struct ToolResultPayload {
+    tool_call_id: String,
+    content: String,
+}
+
+pub fn encode_tool_result(tool_call_id: String, content: String) -> String {
+    serde_json::to_string(&ToolResultPayload { tool_call_id, content }).unwrap()
 }
 
 fn str_to_msg(role: Role, msg: String) -> ChatCompletionRequestMessage {
     match role {
         Role::System => ChatCompletionRequestSystemMessageArgs::default().content(msg).build().unwrap().into(),
         Role::User => ChatCompletionRequestUserMessageArgs::default().content(msg).build().unwrap().into(),
-        Role::Assistant => ChatCompletionRequestAssistantMessageArgs::default().content(msg).build().unwrap().into(),
-        Role::Tool => ChatCompletionRequestToolMessageArgs::default().content(msg).build().unwrap().into(),
+        Role::Assistant => match serde_json::from_str::<Vec<ChatCompletionMessageToolCall>>(&msg) {
+            // A tool-calling turn was persisted as its serialized tool_calls array;
+            // rebuild the same assistant message so the following Role::Tool
+            // entries have a valid tool_call_id to answer.
+            Ok(tool_calls) => ChatCompletionRequestAssistantMessageArgs::default().tool_calls(tool_calls).build().unwrap().into(),
+            Err(_) => ChatCompletionRequestAssistantMessageArgs::default().content(msg).build().unwrap().into(),
+        },
+        Role::Tool => {
+            let (tool_call_id, content) = match serde_json::from_str::<ToolResultPayload>(&msg) {
+                Ok(payload) => (payload.tool_call_id, payload.content),
+                Err(_) => (String::new(), msg),
+            };
+            ChatCompletionRequestToolMessageArgs::default().tool_call_id(tool_call_id).content(content).build().unwrap().into()
+        }
         Role::Function => ChatCompletionRequestFunctionMessageArgs::default().content(msg).build().unwrap().into(),
     }
 }
 
+fn message_role(msg: &ChatCompletionRequestMessage) -> Role {
+    match msg {
+        ChatCompletionRequestMessage::System(_) => Role::System,
+        ChatCompletionRequestMessage::User(_) => Role::User,
+        ChatCompletionRequestMessage::Assistant(_) => Role::Assistant,
+        ChatCompletionRequestMessage::Tool(_) => Role::Tool,
+        ChatCompletionRequestMessage::Function(_) => Role::Function,
+    }
+}
+
+fn message_text(msg: &ChatCompletionRequestMessage) -> String {
+    match msg {
+        ChatCompletionRequestMessage::System(m) => m.content.clone(),
+        ChatCompletionRequestMessage::User(m) => match &m.content {
+            ChatCompletionRequestUserMessageContent::Text(t) => t.clone(),
+            _ => String::new(),
+        },
+        ChatCompletionRequestMessage::Assistant(m) => m.content.clone().unwrap_or_default(),
+        ChatCompletionRequestMessage::Tool(m) => m.content.clone(),
+        ChatCompletionRequestMessage::Function(m) => m.content.clone().unwrap_or_default(),
+    }
+}
+
+// Rough chars/4 heuristic, good enough to decide when a chat is getting long.
+fn estimate_tokens(msgs: &[ChatCompletionRequestMessage]) -> usize {
+    msgs.iter().map(|m| message_text(m).chars().count() / 4).sum()
+}
+
 fn now_sec() -> u64 {
-    SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_secs()
 }
@@ -1,5 +1,8 @@
 mod kinda_db;
 mod bot_flow;
+mod tools;
+mod roles;
+mod rag;
 
 use async_openai::Client;
 use config::Config;
@@ -9,11 +12,26 @@ use teloxide::{Bot, dptree};
 use teloxide::prelude::Dispatcher;
 use teloxide::types::ChatId;
 use crate::kinda_db::KindaDb;
+use crate::rag::VectorStore;
+use crate::tools::ToolRegistry;
 
 #[derive(Deserialize, Clone)]
 pub struct AppConfig {
     admin_id: ChatId,
     db_path: String,
+    // Comma-separated list of tool names to keep disabled, e.g. "fetch_url_title".
+    #[serde(default)]
+    disabled_tools: String,
+}
+
+impl AppConfig {
+    fn disabled_tools(&self) -> Vec<String> {
+        self.disabled_tools
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
 }
 
 #[tokio::main]
@@ -35,13 +53,15 @@ async fn main() {
         .unwrap();
 
     let db = KindaDb::new(config.db_path.clone()).await;
+    let vector_store = VectorStore::new(config.db_path.clone()).await;
     let bot = Bot::from_env();
     let gpt_client = Client::new();
+    let tool_registry = ToolRegistry::new(&config.disabled_tools());
 
     log::info!("councillor bot started...");
 
     Dispatcher::builder(bot, bot_flow::schema())
-        .dependencies(dptree::deps![db, gpt_client, config])
+        .dependencies(dptree::deps![db, gpt_client, config, tool_registry, vector_store])
         .enable_ctrlc_handler()
         .build()
         .dispatch()
@@ -0,0 +1,211 @@
+use std::sync::{Arc, Mutex};
+
+use async_openai::Client;
+use async_openai::config::OpenAIConfig;
+use async_openai::types::CreateEmbeddingRequestArgs;
+use rusqlite::{params, Connection};
+use teloxide::prelude::ChatId;
+
+// Document-grounded answers (RAG): documents a user sends get chunked, embedded
+// through the OpenAI embeddings endpoint and stored here; `chat_msg` then embeds
+// the incoming question and injects the closest chunks as context.
+const EMBEDDING_MODEL: &str = "text-embedding-3-small";
+
+// ~500-token chunks (roughly 4 chars/token) with a small overlap so a fact
+// split across a chunk boundary isn't lost entirely.
+const CHUNK_CHARS: usize = 2000;
+const CHUNK_OVERLAP_CHARS: usize = 200;
+
+pub const DEFAULT_TOP_K: usize = 4;
+// Keeps the injected context from eating the whole prompt budget.
+pub const MAX_CONTEXT_CHARS: usize = 6000;
+
+#[derive(Clone)]
+pub struct VectorStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl VectorStore {
+    pub async fn new(path: String) -> VectorStore {
+        let db_file = format!("{}/rag.db", path);
+
+        let conn = tokio::task::spawn_blocking(move || {
+            let conn = Connection::open(&db_file).expect("failed to open rag.db");
+            init_schema(&conn);
+            conn
+        })
+            .await
+            .expect("rag store init worker panicked");
+
+        VectorStore {
+            conn: Arc::new(Mutex::new(conn)),
+        }
+    }
+
+    // Chunks, embeds and stores a document for later retrieval. Returns the
+    // number of chunks it was split into.
+    pub async fn ingest(
+        &self,
+        chat_id: ChatId,
+        source: String,
+        text: String,
+        gpt_client: &Client<OpenAIConfig>,
+    ) -> anyhow::Result<usize> {
+        let chunks = chunk_text(&text);
+        if chunks.is_empty() {
+            return Ok(0);
+        }
+
+        let embeddings = embed(gpt_client, chunks.clone()).await?;
+        let rows: Vec<(String, Vec<f32>)> = chunks.into_iter().zip(embeddings).collect();
+        let count = rows.len();
+
+        self.with_conn(move |conn| {
+            for (content, embedding) in rows {
+                conn.execute(
+                    "INSERT INTO chunks (chat_id, source, content, embedding, model) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![chat_id.0, source, content, encode_embedding(&embedding), EMBEDDING_MODEL],
+                )?;
+            }
+            Ok(())
+        }).await?;
+
+        Ok(count)
+    }
+
+    pub async fn forget(&self, chat_id: ChatId) -> rusqlite::Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute("DELETE FROM chunks WHERE chat_id = ?1", params![chat_id.0])?;
+            Ok(())
+        }).await
+    }
+
+    // Brute-force cosine-similarity search over the chat's chunks; returns the
+    // top `k` (source, text) pairs, most similar first.
+    pub async fn search(&self, chat_id: ChatId, query_embedding: &[f32], k: usize) -> rusqlite::Result<Vec<(String, String)>> {
+        let rows: Vec<(String, String, Vec<f32>, String)> = self.with_conn(move |conn| {
+            let mut stmt = conn.prepare("SELECT source, content, embedding, model FROM chunks WHERE chat_id = ?1")?;
+
+            stmt.query_map(params![chat_id.0], |row| {
+                let source: String = row.get(0)?;
+                let content: String = row.get(1)?;
+                let embedding_bytes: Vec<u8> = row.get(2)?;
+                let model: String = row.get(3)?;
+                Ok((source, content, decode_embedding(&embedding_bytes), model))
+            })?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        }).await?;
+
+        let mut scored: Vec<(f32, String, String)> = rows
+            .into_iter()
+            // A mismatched embedding model means a different dimensionality; skip
+            // rather than compare vectors that can't be compared.
+            .filter(|(_, _, _, model)| model == EMBEDDING_MODEL)
+            .map(|(source, content, embedding, _)| (cosine_similarity(query_embedding, &embedding), source, content))
+            .collect();
+
+        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        Ok(scored.into_iter().take(k).map(|(_, source, content)| (source, content)).collect())
+    }
+
+    // Runs a blocking rusqlite call on a dedicated worker thread. Callers
+    // propagate `rusqlite::Error` instead of unwrapping inside the closure, so
+    // a failing query returns an error to the one request that hit it rather
+    // than panicking the worker thread and poisoning the shared mutex for
+    // every other chat.
+    async fn with_conn<T, F>(&self, f: F) -> rusqlite::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&conn)
+        })
+            .await
+            .expect("rag store worker thread panicked")
+    }
+}
+
+fn init_schema(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS chunks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            chat_id INTEGER NOT NULL,
+            source TEXT NOT NULL,
+            content TEXT NOT NULL,
+            embedding BLOB NOT NULL,
+            model TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_chunks_chat_id ON chunks(chat_id);",
+    ).expect("failed to initialize rag.db schema");
+}
+
+pub async fn embed_query(gpt_client: &Client<OpenAIConfig>, text: &str) -> anyhow::Result<Vec<f32>> {
+    embed(gpt_client, vec![text.to_string()])
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("embeddings API returned no vectors"))
+}
+
+async fn embed(gpt_client: &Client<OpenAIConfig>, inputs: Vec<String>) -> anyhow::Result<Vec<Vec<f32>>> {
+    let request = CreateEmbeddingRequestArgs::default()
+        .model(EMBEDDING_MODEL)
+        .input(inputs)
+        .build()?;
+
+    let response = gpt_client.embeddings().create(request).await?;
+    Ok(response.data.into_iter().map(|d| d.embedding).collect())
+}
+
+fn chunk_text(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.is_empty() {
+        return vec![];
+    }
+
+    let mut chunks = vec![];
+    let mut start = 0;
+
+    while start < chars.len() {
+        let end = (start + CHUNK_CHARS).min(chars.len());
+        chunks.push(chars[start..end].iter().collect());
+
+        if end == chars.len() {
+            break;
+        }
+
+        start = end.saturating_sub(CHUNK_OVERLAP_CHARS);
+    }
+
+    chunks
+}
+
+fn encode_embedding(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|f| f.to_le_bytes()).collect()
+}
+
+fn decode_embedding(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return f32::MIN;
+    }
+
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
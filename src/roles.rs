@@ -0,0 +1,48 @@
+// Named personas a chat can switch between, each carrying its own system prompt.
+// Inspired by aichat's `roles`/`agents`: one deployment, several assistants.
+pub struct RoleDef {
+    pub id: &'static str,
+    pub title: &'static str,
+    pub system_prompt: &'static str,
+}
+
+pub const DEFAULT_ROLE: &str = "secretary";
+
+pub fn built_in_roles() -> &'static [RoleDef] {
+    &[
+        RoleDef {
+            id: "secretary",
+            title: "Секретарь",
+            system_prompt: "Ты ассистент и секретарь. Твой основной язык русский. \
+                Ты помогаешь вести деловую переписку и искать нужную информацию. \
+                Так же ты хороший переводчик и владеешь всеми языками мира. \
+                Ты опытен в составлении статей и имеешь широкий кругозор в науках и \
+                программировании.",
+        },
+        RoleDef {
+            id: "translator",
+            title: "Переводчик",
+            system_prompt: "Ты профессиональный переводчик, владеющий всеми языками мира. \
+                Переводи текст пользователя максимально точно, сохраняя стиль и тон \
+                оригинала, и уточняй исходный язык, если он неочевиден.",
+        },
+        RoleDef {
+            id: "coder",
+            title: "Помощник программиста",
+            system_prompt: "Ты опытный инженер-программист. Помогай писать, объяснять и \
+                ревьюить код, предлагай идиоматичные решения для используемого языка и \
+                указывай на возможные баги и edge case'ы.",
+        },
+    ]
+}
+
+pub fn find_role(id: &str) -> Option<&'static RoleDef> {
+    built_in_roles().iter().find(|r| r.id == id)
+}
+
+pub fn system_prompt_for(id: &str) -> &'static str {
+    find_role(id)
+        .or_else(|| find_role(DEFAULT_ROLE))
+        .map(|r| r.system_prompt)
+        .expect("DEFAULT_ROLE must be a built-in role")
+}
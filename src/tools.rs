@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use async_openai::types::{ChatCompletionTool, ChatCompletionToolType, FunctionObject};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+// A callable tool the model can invoke mid-conversation via OpenAI function calling.
+// Mirrors aichat's `dangerously_functions_filter` idea: anything with side effects
+// (network calls, etc) can be turned off per-deployment through `AppConfig`.
+#[async_trait]
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+    fn description(&self) -> &str;
+    fn parameters(&self) -> Value;
+    async fn call(&self, args: Value) -> anyhow::Result<String>;
+
+    fn as_openai_tool(&self) -> ChatCompletionTool {
+        ChatCompletionTool {
+            r#type: ChatCompletionToolType::Function,
+            function: FunctionObject {
+                name: self.name().to_string(),
+                description: Some(self.description().to_string()),
+                parameters: Some(self.parameters()),
+                strict: None,
+            },
+        }
+    }
+}
+
+struct MathTool;
+
+#[async_trait]
+impl Tool for MathTool {
+    fn name(&self) -> &str {
+        "evaluate_math"
+    }
+
+    fn description(&self) -> &str {
+        "Evaluates a basic arithmetic expression (+, -, *, /, parentheses) and returns the result"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "expression": {
+                    "type": "string",
+                    "description": "An arithmetic expression, e.g. \"(2 + 3) * 4\""
+                }
+            },
+            "required": ["expression"]
+        })
+    }
+
+    async fn call(&self, args: Value) -> anyhow::Result<String> {
+        let expression = args
+            .get("expression")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow::anyhow!("missing \"expression\" argument"))?;
+
+        let result = eval_arithmetic(expression)?;
+        Ok(result.to_string())
+    }
+}
+
+struct CurrentTimeTool;
+
+#[async_trait]
+impl Tool for CurrentTimeTool {
+    fn name(&self) -> &str {
+        "current_time"
+    }
+
+    fn description(&self) -> &str {
+        "Returns the current UTC date and time"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({ "type": "object", "properties": {} })
+    }
+
+    async fn call(&self, _args: Value) -> anyhow::Result<String> {
+        Ok(chrono::Utc::now().to_rfc3339())
+    }
+}
+
+struct UrlTitleTool;
+
+#[async_trait]
+impl Tool for UrlTitleTool {
+    fn name(&self) -> &str {
+        "fetch_url_title"
+    }
+
+    fn description(&self) -> &str {
+        "Fetches a web page by URL and returns its <title>"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "url": {
+                    "type": "string",
+                    "description": "The URL to fetch, including scheme, e.g. \"https://example.com\""
+                }
+            },
+            "required": ["url"]
+        })
+    }
+
+    async fn call(&self, args: Value) -> anyhow::Result<String> {
+        let url = args
+            .get("url")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow::anyhow!("missing \"url\" argument"))?;
+
+        let url = reqwest::Url::parse(url)?;
+        guard_against_ssrf(&url).await?;
+
+        let body = reqwest::get(url).await?.text().await?;
+        extract_title(&body).ok_or_else(|| anyhow::anyhow!("no <title> found"))
+    }
+}
+
+fn extract_title(html: &str) -> Option<String> {
+    let lower = html.to_lowercase();
+    let start = lower.find("<title")? ;
+    let open_end = html[start..].find('>')? + start + 1;
+    let close = lower[open_end..].find("</title>")? + open_end;
+    Some(html[open_end..close].trim().to_string())
+}
+
+// The URL is attacker-controlled (the model picks it from whatever the user
+// typed), so reject anything that isn't a plain http(s) fetch of a public
+// address before we let reqwest touch it — otherwise the bot can be made to
+// probe loopback/RFC1918/link-local ranges and cloud metadata endpoints
+// (169.254.169.254) from wherever it happens to be deployed.
+async fn guard_against_ssrf(url: &reqwest::Url) -> anyhow::Result<()> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        anyhow::bail!("unsupported URL scheme \"{}\"", url.scheme());
+    }
+
+    let host = url.host_str().ok_or_else(|| anyhow::anyhow!("URL has no host"))?;
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let addrs = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| anyhow::anyhow!("could not resolve host \"{}\": {}", host, e))?;
+
+    for addr in addrs {
+        if is_internal(addr.ip()) {
+            anyhow::bail!("refusing to fetch internal address {}", addr.ip());
+        }
+    }
+
+    Ok(())
+}
+
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_internal(IpAddr::V4(v4)),
+            None => v6.is_loopback() || v6.is_unspecified() || v6.is_unique_local() || v6.is_unicast_link_local(),
+        },
+    }
+}
+
+// Caps how deeply `(`/unary `-` can nest. Without this, an expression with
+// thousands of `(` characters recurses until it blows the stack — a Rust
+// stack overflow aborts the whole process rather than unwinding as a
+// catchable panic, so every chat would go down with it.
+const MAX_EXPRESSION_DEPTH: usize = 64;
+
+// Tiny recursive-descent evaluator so the math tool has no external crate to depend on.
+fn eval_arithmetic(expression: &str) -> anyhow::Result<f64> {
+    let tokens: Vec<char> = expression.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut pos = 0usize;
+    let value = parse_expr(&tokens, &mut pos, 0)?;
+
+    if pos != tokens.len() {
+        anyhow::bail!("unexpected character at position {}", pos);
+    }
+
+    Ok(value)
+}
+
+fn parse_expr(tokens: &[char], pos: &mut usize, depth: usize) -> anyhow::Result<f64> {
+    let mut value = parse_term(tokens, pos, depth)?;
+
+    while let Some(&op) = tokens.get(*pos) {
+        match op {
+            '+' | '-' => {
+                *pos += 1;
+                let rhs = parse_term(tokens, pos, depth)?;
+                value = if op == '+' { value + rhs } else { value - rhs };
+            }
+            _ => break,
+        }
+    }
+
+    Ok(value)
+}
+
+fn parse_term(tokens: &[char], pos: &mut usize, depth: usize) -> anyhow::Result<f64> {
+    let mut value = parse_factor(tokens, pos, depth)?;
+
+    while let Some(&op) = tokens.get(*pos) {
+        match op {
+            '*' | '/' => {
+                *pos += 1;
+                let rhs = parse_factor(tokens, pos, depth)?;
+                value = if op == '*' { value * rhs } else { value / rhs };
+            }
+            _ => break,
+        }
+    }
+
+    Ok(value)
+}
+
+fn parse_factor(tokens: &[char], pos: &mut usize, depth: usize) -> anyhow::Result<f64> {
+    if depth > MAX_EXPRESSION_DEPTH {
+        anyhow::bail!("expression nested too deeply (max depth {})", MAX_EXPRESSION_DEPTH);
+    }
+
+    match tokens.get(*pos) {
+        Some('(') => {
+            *pos += 1;
+            let value = parse_expr(tokens, pos, depth + 1)?;
+            match tokens.get(*pos) {
+                Some(')') => *pos += 1,
+                _ => anyhow::bail!("expected closing parenthesis"),
+            }
+            Ok(value)
+        }
+        Some('-') => {
+            *pos += 1;
+            Ok(-parse_factor(tokens, pos, depth + 1)?)
+        }
+        _ => {
+            let start = *pos;
+            while tokens
+                .get(*pos)
+                .map(|c| c.is_ascii_digit() || *c == '.')
+                .unwrap_or(false)
+            {
+                *pos += 1;
+            }
+
+            if start == *pos {
+                anyhow::bail!("expected a number at position {}", start);
+            }
+
+            let number: String = tokens[start..*pos].iter().collect();
+            number.parse::<f64>().map_err(|e| anyhow::anyhow!(e))
+        }
+    }
+}
+
+// Registry of tools available to `chat_msg`, filtered by the operator-controlled
+// `disabled_tools` list in `AppConfig`.
+#[derive(Clone)]
+pub struct ToolRegistry {
+    tools: HashMap<String, Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    pub fn new(disabled: &[String]) -> ToolRegistry {
+        let all: Vec<Arc<dyn Tool>> = vec![
+            Arc::new(MathTool),
+            Arc::new(CurrentTimeTool),
+            Arc::new(UrlTitleTool),
+        ];
+
+        let tools = all
+            .into_iter()
+            .filter(|tool| !disabled.iter().any(|name| name == tool.name()))
+            .map(|tool| (tool.name().to_string(), tool))
+            .collect();
+
+        ToolRegistry { tools }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    pub fn openai_tools(&self) -> Vec<ChatCompletionTool> {
+        self.tools.values().map(|tool| tool.as_openai_tool()).collect()
+    }
+
+    pub async fn dispatch(&self, name: &str, args: Value) -> anyhow::Result<String> {
+        match self.tools.get(name) {
+            Some(tool) => tool.call(args).await,
+            None => anyhow::bail!("unknown tool \"{}\"", name),
+        }
+    }
+}